@@ -1,10 +1,12 @@
 //! A variety of helpers for writing transformations.  Meant to be glob-imported by transform
 //! implementation modules.
 use rustc::hir;
+use rustc::hir::def::{CtorKind, Def};
 use rustc::hir::def_id::DefId;
 use rustc::session::Session;
-use rustc::ty::Ty;
+use rustc::ty::{Ty, TyCtxt};
 use rustc::ty::item_path::{ItemPathBuffer, RootMode};
+use rustc::ty::subst::Substs;
 use syntax::ast::{self, TyKind};    // `Ty` refers to `rustc::ty::Ty`.
 use syntax::ast::{NodeId, DUMMY_NODE_ID};
 use syntax::ast::{Expr, ExprKind};
@@ -26,12 +28,19 @@ pub use path_edit::{self, fold_resolved_paths, fold_resolved_paths_with_id};
 pub use fn_edit::{fold_fns, fold_fns_multi};
 pub use lr_expr::{self, fold_expr_with_context};
 pub use output_exprs::fold_output_exprs;
+pub use prec::{HolePos, needs_parens};
+pub use spanless_eq::{SpanlessEqCtxt, exprs_equal, hash_expr};
+pub use const_eval::ConstValue;
+pub use higher::{self, for_loop, range, if_let, while_let, question_mark};
+pub use usage::{Uses, local_uses};
 
 use bindings::Bindings;
 use command::CommandState;
+use const_eval;
 use driver;
 use fold::Fold;
 use matcher::Pattern;
+use prec;
 use reflect;
 use util::HirDefExt;
 use util::IntoSymbol;
@@ -48,6 +57,24 @@ pub fn replace_expr<T: Fold>(st: &CommandState,
     fold_match(st, cx, pat, ast, |_, bnd| repl.clone().subst(st, cx, &bnd))
 }
 
+/// Like `replace_expr`, but wraps any substituted subexpression in parens when its precedence is
+/// too low for the position `repl` puts it in.  Plain `replace_expr` splices a bound
+/// metavariable's expr in as-is, which silently changes meaning when, say, `$e` is bound to
+/// `a + b` and `repl` is `$e * 2`: the result parses as `a + b * 2`, not `(a + b) * 2`.  This
+/// entry point is opt-in since existing callers that already build their own parens (or that
+/// only ever bind atoms) don't need the extra rewrite pass.
+pub fn replace_expr_prec<T: Fold>(st: &CommandState,
+                                  cx: &driver::Ctxt,
+                                  ast: T,
+                                  pat: &str,
+                                  repl: &str) -> <T as Fold>::Result {
+    let pat = parse_expr(cx.session(), pat);
+    let repl = parse_expr(cx.session(), repl);
+    fold_match(st, cx, pat, ast, |_, bnd| {
+        prec::rewrap_for_precedence(repl.clone().subst(st, cx, &bnd))
+    })
+}
+
 /// Replace all instances of the statement sequence `pat` with `repl`.
 pub fn replace_stmts<T: Fold>(st: &CommandState,
                               cx: &driver::Ctxt,
@@ -86,6 +113,73 @@ pub fn find_first<P, T>(st: &CommandState,
 }
 
 
+/// What kind of definition a `DefId` names.  Plain `DefId`s are opaque, so telling a tuple-struct
+/// constructor (`Foo(x)`) from the struct type it builds (`Foo`), or a unit enum variant from its
+/// enum, requires keeping the `Def` namespace/kind information around instead of discarding it
+/// the moment a `DefId` is extracted.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DefKind {
+    Mod,
+    Struct,
+    Union,
+    Enum,
+    Variant,
+    Trait,
+    TyAlias,
+    TraitAlias,
+    AssociatedTy,
+    PrimitiveTy,
+    TyParam,
+    Fn,
+    Method,
+    Const,
+    ConstParam,
+    AssociatedConst,
+    Static,
+    StructCtor(CtorKind),
+    VariantCtor(CtorKind),
+    Local,
+    Macro,
+    Other,
+}
+
+fn def_kind_of(def: &Def) -> DefKind {
+    match *def {
+        Def::Mod(_) => DefKind::Mod,
+        Def::Struct(_) => DefKind::Struct,
+        Def::Union(_) => DefKind::Union,
+        Def::Enum(_) => DefKind::Enum,
+        Def::Variant(_) => DefKind::Variant,
+        Def::Trait(_) => DefKind::Trait,
+        Def::TyAlias(_) => DefKind::TyAlias,
+        Def::TraitAlias(_) => DefKind::TraitAlias,
+        Def::AssociatedTy(_) => DefKind::AssociatedTy,
+        Def::PrimTy(_) => DefKind::PrimitiveTy,
+        Def::TyParam(_) => DefKind::TyParam,
+        Def::Fn(_) => DefKind::Fn,
+        Def::Method(_) => DefKind::Method,
+        Def::Const(_) => DefKind::Const,
+        Def::ConstParam(_) => DefKind::ConstParam,
+        Def::AssociatedConst(_) => DefKind::AssociatedConst,
+        Def::Static(_, _) => DefKind::Static,
+        Def::StructCtor(_, kind) => DefKind::StructCtor(kind),
+        Def::VariantCtor(_, kind) => DefKind::VariantCtor(kind),
+        Def::Local(_) => DefKind::Local,
+        Def::Macro(..) => DefKind::Macro,
+        _ => DefKind::Other,
+    }
+}
+
+/// The function/method a `Call` or `MethodCall` invokes, together with its instantiated type
+/// arguments and result type -- the information needed to branch on monomorphized type args (e.g.
+/// choosing a different replacement for `mem::size_of::<u32>()` vs `size_of::<*mut T>()`) without
+/// re-deriving it node by node with `node_type`.
+pub struct CalleeInfo<'gcx> {
+    pub def_id: DefId,
+    pub substs: &'gcx Substs<'gcx>,
+    pub output: Ty<'gcx>,
+}
+
 /// `driver::Ctxt` extension trait.
 pub trait DriverCtxtExt<'gcx> {
     /// Get the `ty::Ty` computed for a node.
@@ -103,14 +197,38 @@ pub trait DriverCtxtExt<'gcx> {
     /// Get the target `DefId` of a path expr.
     fn resolve_expr(&self, e: &Expr) -> DefId;
     fn try_resolve_expr(&self, e: &Expr) -> Option<DefId>;
+    /// Like `try_resolve_expr`, but also returns what kind of def it resolved to.
+    fn resolve_expr_kind(&self, e: &Expr) -> Option<(DefId, DefKind)>;
 
     /// Get the target `DefId` of a path ty.
     fn resolve_ty(&self, e: &ast::Ty) -> DefId;
     fn try_resolve_ty(&self, e: &ast::Ty) -> Option<DefId>;
+    /// Like `try_resolve_ty`, but also returns what kind of def it resolved to.
+    fn resolve_ty_kind(&self, e: &ast::Ty) -> Option<(DefId, DefKind)>;
 
     /// Get the `DefId` of the function or method being called by a `Call` or `MethodCall` expr.
     fn callee(&self, e: &Expr) -> DefId;
     fn opt_callee(&self, e: &Expr) -> Option<DefId>;
+
+    /// Like `callee`/`opt_callee`, but also returns the callee's instantiated generic arguments
+    /// and result type.
+    fn callee_info(&self, e: &Expr) -> CalleeInfo<'gcx>;
+    fn opt_callee_info(&self, e: &Expr) -> Option<CalleeInfo<'gcx>>;
+
+    /// Evaluate `e` as a constant expression, or return `None` if some subexpression isn't
+    /// constant (or uses an operation/type this evaluator doesn't model).
+    fn eval_const_expr(&self, e: &Expr) -> Option<ConstValue>;
+
+    /// What kind of definition `id` is.
+    fn def_kind(&self, id: DefId) -> DefKind;
+    /// Is `id` a struct or enum-variant constructor (a synthesized fn item, not the type itself)?
+    fn is_ctor(&self, id: DefId) -> bool;
+    /// Is `id` specifically a tuple-like constructor, of a tuple struct or a tuple enum variant
+    /// (as opposed to a unit one's, which takes no arguments and is used as a value rather than
+    /// called)?
+    fn is_tuple_struct_ctor(&self, id: DefId) -> bool;
+    /// Map a constructor's `DefId` back to the `DefId` of the struct/variant it builds.
+    fn ctor_parent(&self, id: DefId) -> Option<DefId>;
 }
 
 impl<'a, 'hir, 'gcx, 'tcx> DriverCtxtExt<'gcx> for driver::Ctxt<'a, 'hir, 'gcx, 'tcx> {
@@ -164,6 +282,18 @@ impl<'a, 'hir, 'gcx, 'tcx> DriverCtxtExt<'gcx> for driver::Ctxt<'a, 'hir, 'gcx,
             .unwrap_or_else(|| panic!("expr does not resolve to a def: {:?}", e))
     }
 
+    fn resolve_expr_kind(&self, e: &Expr) -> Option<(DefId, DefKind)> {
+        let node = match_or!([self.hir_map().find(e.id)] Some(x) => x;
+                             return None);
+        let e = match_or!([node] hir::map::NodeExpr(e) => e;
+                          return None);
+        let qpath = match_or!([e.node] hir::ExprPath(ref q) => q;
+                              return None);
+        let path = match_or!([*qpath] hir::QPath::Resolved(_, ref path) => path;
+                             return None);
+        path.def.opt_def_id().map(|id| (id, def_kind_of(&path.def)))
+    }
+
     fn try_resolve_ty(&self, t: &ast::Ty) -> Option<DefId> {
         let node = match_or!([self.hir_map().find(t.id)] Some(x) => x;
                              return None);
@@ -181,6 +311,18 @@ impl<'a, 'hir, 'gcx, 'tcx> DriverCtxtExt<'gcx> for driver::Ctxt<'a, 'hir, 'gcx,
             .unwrap_or_else(|| panic!("ty does not resolve to a def: {:?}", t))
     }
 
+    fn resolve_ty_kind(&self, t: &ast::Ty) -> Option<(DefId, DefKind)> {
+        let node = match_or!([self.hir_map().find(t.id)] Some(x) => x;
+                             return None);
+        let t = match_or!([node] hir::map::NodeTy(t) => t;
+                          return None);
+        let qpath = match_or!([t.node] hir::TyPath(ref q) => q;
+                              return None);
+        let path = match_or!([*qpath] hir::QPath::Resolved(_, ref path) => path;
+                             return None);
+        path.def.opt_def_id().map(|id| (id, def_kind_of(&path.def)))
+    }
+
     fn opt_callee(&self, e: &Expr) -> Option<DefId> {
         if e.id == DUMMY_NODE_ID {
             return None;
@@ -210,4 +352,71 @@ impl<'a, 'hir, 'gcx, 'tcx> DriverCtxtExt<'gcx> for driver::Ctxt<'a, 'hir, 'gcx,
     fn callee(&self, e: &Expr) -> DefId {
         self.opt_callee(e).expect("callee: expr is not a call")
     }
+
+    fn opt_callee_info(&self, e: &Expr) -> Option<CalleeInfo<'gcx>> {
+        if e.id == DUMMY_NODE_ID {
+            return None;
+        }
+        let parent = self.hir_map().get_parent(e.id);
+        let parent_body = match_or!([self.hir_map().maybe_body_owned_by(parent)]
+                                    Some(x) => x; return None);
+        let tables = self.ty_ctxt().body_tables(parent_body);
+
+        let (def_id, substs) = match e.node {
+            ExprKind::Call(ref func, _) => {
+                let def_id = match self.try_resolve_expr(func) {
+                    Some(def_id) => def_id,
+                    None => tables.type_dependent_defs.get(&func.id).and_then(|d| d.opt_def_id())?,
+                };
+                (def_id, tables.node_substs(func.id))
+            }
+            ExprKind::MethodCall(..) => {
+                let def_id = tables.type_dependent_defs.get(&e.id).and_then(|d| d.opt_def_id())?;
+                (def_id, tables.node_substs(e.id))
+            }
+            _ => return None,
+        };
+
+        Some(CalleeInfo {
+            def_id,
+            substs,
+            output: self.node_type(e.id),
+        })
+    }
+
+    fn callee_info(&self, e: &Expr) -> CalleeInfo<'gcx> {
+        self.opt_callee_info(e).expect("callee_info: expr is not a call")
+    }
+
+    fn eval_const_expr(&self, e: &Expr) -> Option<ConstValue> {
+        const_eval::eval_const_expr(self, e)
+    }
+
+    fn def_kind(&self, id: DefId) -> DefKind {
+        match self.ty_ctxt().describe_def(id) {
+            Some(def) => def_kind_of(&def),
+            None => DefKind::Other,
+        }
+    }
+
+    fn is_ctor(&self, id: DefId) -> bool {
+        match self.def_kind(id) {
+            DefKind::StructCtor(_) | DefKind::VariantCtor(_) => true,
+            _ => false,
+        }
+    }
+
+    fn is_tuple_struct_ctor(&self, id: DefId) -> bool {
+        match self.def_kind(id) {
+            DefKind::StructCtor(CtorKind::Fn) | DefKind::VariantCtor(CtorKind::Fn) => true,
+            _ => false,
+        }
+    }
+
+    fn ctor_parent(&self, id: DefId) -> Option<DefId> {
+        if !self.is_ctor(id) {
+            return None;
+        }
+        Some(self.ty_ctxt().parent_def_id(id).unwrap_or(id))
+    }
 }
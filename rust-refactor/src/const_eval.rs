@@ -0,0 +1,405 @@
+//! Constant-folding for transpiled C expressions.  Lets transforms (dead-branch elimination,
+//! macro-constant inlining) turn a constant subexpression into a literal without re-implementing
+//! an interpreter of their own.
+use rustc::hir;
+use rustc::hir::def_id::DefId;
+use rustc::ty::TypeVariants;
+use syntax::ast::{self, Expr, ExprKind, LitKind, UnOp, BinOpKind, IntTy, UintTy};
+
+use api::DriverCtxtExt;
+use driver;
+
+/// Result of evaluating a constant expression.  Deliberately small: just enough to fold the
+/// arithmetic and comparisons C-derived code actually uses in array bounds, bit widths, and
+/// branch conditions.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ConstValue {
+    Int(i128, IntTy),
+    Uint(u128, UintTy),
+    Bool(bool),
+    Char(char),
+    F32(f32),
+    F64(f64),
+}
+
+impl ConstValue {
+    fn as_i128(&self) -> Option<i128> {
+        match *self {
+            ConstValue::Int(v, _) => Some(v),
+            ConstValue::Uint(v, _) => Some(v as i128),
+            _ => None,
+        }
+    }
+
+    fn is_truthy(&self) -> Option<bool> {
+        match *self {
+            ConstValue::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+}
+
+/// Recursively evaluate `e` as a constant, or return `None` if it (or any subexpression) isn't
+/// one -- a non-constant operand, division/shift by zero, or a type/op this evaluator doesn't
+/// model.
+pub fn eval_const_expr<'a, 'hir, 'gcx, 'tcx>(cx: &driver::Ctxt<'a, 'hir, 'gcx, 'tcx>,
+                                             e: &Expr) -> Option<ConstValue> {
+    eval_inner(cx, e, &mut Vec::new())
+}
+
+fn eval_inner<'a, 'hir, 'gcx, 'tcx>(cx: &driver::Ctxt<'a, 'hir, 'gcx, 'tcx>,
+                                    e: &Expr,
+                                    visiting: &mut Vec<DefId>) -> Option<ConstValue> {
+    match e.node {
+        ExprKind::Lit(ref lit) => lit_to_const(cx, e, lit),
+
+        ExprKind::Paren(ref inner) => eval_inner(cx, inner, visiting),
+
+        ExprKind::Unary(op, ref inner) => {
+            let v = eval_inner(cx, inner, visiting)?;
+            match (op, v) {
+                (UnOp::Neg, ConstValue::Int(i, ty)) => {
+                    let bits = int_bits(ty);
+                    i.checked_neg().filter(|i| int_fits(*i, bits)).map(|i| ConstValue::Int(i, ty))
+                }
+                (UnOp::Neg, ConstValue::F32(f)) => Some(ConstValue::F32(-f)),
+                (UnOp::Neg, ConstValue::F64(f)) => Some(ConstValue::F64(-f)),
+                (UnOp::Not, ConstValue::Bool(b)) => Some(ConstValue::Bool(!b)),
+                // Bitwise NOT always fits back into the same width: flipping every bit of an
+                // `n`-bit value produces another `n`-bit value, so this just needs to re-sign
+                // the `i128` after masking to `ty`'s width.
+                (UnOp::Not, ConstValue::Int(i, ty)) =>
+                    Some(ConstValue::Int(sign_extend(!i, int_bits(ty)), ty)),
+                (UnOp::Not, ConstValue::Uint(i, ty)) =>
+                    Some(ConstValue::Uint((!i) & uint_mask(uint_bits(ty)), ty)),
+                _ => None,
+            }
+        }
+
+        ExprKind::Binary(op, ref l, ref r) => {
+            let l = eval_inner(cx, l, visiting)?;
+            let r = eval_inner(cx, r, visiting)?;
+            eval_binop(op.node, l, r)
+        }
+
+        ExprKind::Cast(ref inner, ref ty) => {
+            let v = eval_inner(cx, inner, visiting)?;
+            let def_id = cx.try_resolve_ty(ty)?;
+            cast_const(cx, v, def_id)
+        }
+
+        ExprKind::Path(..) => {
+            let def_id = cx.try_resolve_expr(e)?;
+            if visiting.contains(&def_id) {
+                // Cycle in const definitions -- not something a real program can do, but better
+                // to bail out than loop forever while folding a malformed one.
+                return None;
+            }
+            visiting.push(def_id);
+            let result = eval_const_item(cx, def_id, visiting);
+            visiting.pop();
+            result
+        }
+
+        _ => None,
+    }
+}
+
+fn lit_to_const(cx: &driver::Ctxt, e: &Expr, lit: &ast::Lit) -> Option<ConstValue> {
+    match lit.node {
+        LitKind::Bool(b) => Some(ConstValue::Bool(b)),
+        LitKind::Char(c) => Some(ConstValue::Char(c)),
+        LitKind::Int(v, _) => {
+            match cx.node_type(e.id).sty {
+                TypeVariants::TyInt(ty) => Some(ConstValue::Int(v as i128, ty)),
+                TypeVariants::TyUint(ty) => Some(ConstValue::Uint(v as u128, ty)),
+                _ => Some(ConstValue::Int(v as i128, IntTy::I32)),
+            }
+        }
+        LitKind::Float(ref s, _) | LitKind::FloatUnsuffixed(ref s) => {
+            match cx.node_type(e.id).sty {
+                TypeVariants::TyFloat(ast::FloatTy::F32) =>
+                    s.as_str().parse::<f32>().ok().map(ConstValue::F32),
+                _ => s.as_str().parse::<f64>().ok().map(ConstValue::F64),
+            }
+        }
+        _ => None,
+    }
+}
+
+fn eval_binop(op: BinOpKind, l: ConstValue, r: ConstValue) -> Option<ConstValue> {
+    use self::BinOpKind::*;
+
+    if let (Some(a), Some(b)) = (l.is_truthy(), r.is_truthy()) {
+        return match op {
+            And => Some(ConstValue::Bool(a && b)),
+            Or => Some(ConstValue::Bool(a || b)),
+            Eq => Some(ConstValue::Bool(a == b)),
+            Ne => Some(ConstValue::Bool(a != b)),
+            _ => None,
+        };
+    }
+
+    match op {
+        Eq | Ne | Lt | Le | Gt | Ge => {
+            let (a, b) = (l.as_i128()?, r.as_i128()?);
+            let result = match op {
+                Eq => a == b,
+                Ne => a != b,
+                Lt => a < b,
+                Le => a <= b,
+                Gt => a > b,
+                Ge => a >= b,
+                _ => unreachable!(),
+            };
+            Some(ConstValue::Bool(result))
+        }
+
+        Add | Sub | Mul | Div | Rem | BitAnd | BitOr | BitXor | Shl | Shr => {
+            match (l, r) {
+                (ConstValue::Int(a, ty), ConstValue::Int(b, _)) =>
+                    eval_int_binop(op, a, b, ty).map(|v| ConstValue::Int(v, ty)),
+                (ConstValue::Uint(a, ty), ConstValue::Uint(b, _)) =>
+                    eval_uint_binop(op, a, b, ty).map(|v| ConstValue::Uint(v, ty)),
+                (ConstValue::F32(a), ConstValue::F32(b)) =>
+                    eval_float_binop(op, a as f64, b as f64).map(|v| ConstValue::F32(v as f32)),
+                (ConstValue::F64(a), ConstValue::F64(b)) => eval_float_binop(op, a, b).map(ConstValue::F64),
+                _ => None,
+            }
+        }
+
+        _ => None,
+    }
+}
+
+/// Bit width of `ty`, for bounds-checking arithmetic performed in `i128`.  `isize` is assumed to
+/// be 64-bit; transpiled targets narrower than that are rare enough not to special-case here.
+fn int_bits(ty: IntTy) -> u32 {
+    match ty {
+        IntTy::I8 => 8,
+        IntTy::I16 => 16,
+        IntTy::I32 => 32,
+        IntTy::I64 => 64,
+        IntTy::I128 => 128,
+        IntTy::Isize => 64,
+    }
+}
+
+fn uint_bits(ty: UintTy) -> u32 {
+    match ty {
+        UintTy::U8 => 8,
+        UintTy::U16 => 16,
+        UintTy::U32 => 32,
+        UintTy::U64 => 64,
+        UintTy::U128 => 128,
+        UintTy::Usize => 64,
+    }
+}
+
+fn int_fits(v: i128, bits: u32) -> bool {
+    if bits >= 128 {
+        return true;
+    }
+    let max = (1i128 << (bits - 1)) - 1;
+    let min = -max - 1;
+    v >= min && v <= max
+}
+
+fn uint_fits(v: u128, bits: u32) -> bool {
+    bits >= 128 || v < (1u128 << bits)
+}
+
+fn uint_mask(bits: u32) -> u128 {
+    if bits >= 128 { !0u128 } else { (1u128 << bits) - 1 }
+}
+
+/// Reinterpret the low `bits` bits of `v` as a two's-complement signed value of that width.
+fn sign_extend(v: i128, bits: u32) -> i128 {
+    if bits >= 128 {
+        return v;
+    }
+    let masked = (v as u128) & uint_mask(bits);
+    let sign_bit = 1u128 << (bits - 1);
+    if masked & sign_bit != 0 {
+        (masked as i128) - (1i128 << bits)
+    } else {
+        masked as i128
+    }
+}
+
+/// Evaluate `a op b` at `ty`'s actual bit width: the `i128` arithmetic itself can't overflow, so
+/// the real operand width has to be checked explicitly both for shift amounts (`1i8 << 10` isn't a
+/// valid shift, regardless of what `i128` can represent) and for the result (`100i8 + 100i8`
+/// doesn't fit in an `i8` even though it fits easily in `i128`).
+fn eval_int_binop(op: BinOpKind, a: i128, b: i128, ty: IntTy) -> Option<i128> {
+    use self::BinOpKind::*;
+    let bits = int_bits(ty);
+    let result = match op {
+        Add => a.checked_add(b)?,
+        Sub => a.checked_sub(b)?,
+        Mul => a.checked_mul(b)?,
+        Div => if b == 0 { return None; } else { a.checked_div(b)? },
+        Rem => if b == 0 { return None; } else { a.checked_rem(b)? },
+        BitAnd => a & b,
+        BitOr => a | b,
+        BitXor => a ^ b,
+        Shl => {
+            // Compare at full width before narrowing to `u32` for the shift -- `b as u32` would
+            // wrap a shift count that's an exact multiple of 2^32 down to 0, letting the bounds
+            // check pass and `checked_shl(0)` silently return `a` unchanged instead of bailing out.
+            if b < 0 || b >= bits as i128 { return None; }
+            sign_extend(a.checked_shl(b as u32)?, bits)
+        }
+        Shr => {
+            if b < 0 || b >= bits as i128 { return None; }
+            a.checked_shr(b as u32)?
+        }
+        _ => return None,
+    };
+    if int_fits(result, bits) { Some(result) } else { None }
+}
+
+fn eval_uint_binop(op: BinOpKind, a: u128, b: u128, ty: UintTy) -> Option<u128> {
+    use self::BinOpKind::*;
+    let bits = uint_bits(ty);
+    let result = match op {
+        Add => a.checked_add(b)?,
+        Sub => a.checked_sub(b)?,
+        Mul => a.checked_mul(b)?,
+        Div => if b == 0 { return None; } else { a.checked_div(b)? },
+        Rem => if b == 0 { return None; } else { a.checked_rem(b)? },
+        BitAnd => a & b,
+        BitOr => a | b,
+        BitXor => a ^ b,
+        Shl => {
+            // See `eval_int_binop`: compare at full width before narrowing to `u32`.
+            if b >= bits as u128 { return None; }
+            a.checked_shl(b as u32)?
+        }
+        Shr => {
+            if b >= bits as u128 { return None; }
+            a.checked_shr(b as u32)?
+        }
+        _ => return None,
+    };
+    if uint_fits(result, bits) { Some(result) } else { None }
+}
+
+fn eval_float_binop(op: BinOpKind, a: f64, b: f64) -> Option<f64> {
+    use self::BinOpKind::*;
+    match op {
+        Add => Some(a + b),
+        Sub => Some(a - b),
+        Mul => Some(a * b),
+        Div => if b == 0.0 { None } else { Some(a / b) },
+        Rem => if b == 0.0 { None } else { Some(a % b) },
+        _ => None,
+    }
+}
+
+fn cast_const(cx: &driver::Ctxt, v: ConstValue, ty_def_id: DefId) -> Option<ConstValue> {
+    let ty = cx.def_type(ty_def_id);
+    let as_i128 = v.as_i128();
+    match ty.sty {
+        TypeVariants::TyInt(ty) => as_i128.map(|i| ConstValue::Int(i, ty)),
+        TypeVariants::TyUint(ty) => as_i128.map(|i| ConstValue::Uint(i as u128, ty)),
+        TypeVariants::TyBool => as_i128.map(|i| ConstValue::Bool(i != 0)),
+        _ => None,
+    }
+}
+
+/// `const`/`static` items are only reachable through the typechecked HIR (their `DefId` may not
+/// even be local), so their initializers are folded against `hir::Expr` directly rather than
+/// routed back through the `ast::Expr` evaluator above.
+fn eval_const_item<'a, 'hir, 'gcx, 'tcx>(cx: &driver::Ctxt<'a, 'hir, 'gcx, 'tcx>,
+                                         def_id: DefId,
+                                         visiting: &mut Vec<DefId>) -> Option<ConstValue> {
+    let node_id = cx.hir_map().as_local_node_id(def_id)?;
+    let body_id = cx.hir_map().maybe_body_owned_by(node_id)?;
+    let body = cx.hir_map().body(body_id);
+    eval_hir_expr(cx, &body.value, visiting)
+}
+
+fn eval_hir_expr<'a, 'hir, 'gcx, 'tcx>(cx: &driver::Ctxt<'a, 'hir, 'gcx, 'tcx>,
+                                       e: &hir::Expr,
+                                       visiting: &mut Vec<DefId>) -> Option<ConstValue> {
+    match e.node {
+        hir::ExprLit(ref lit) => lit_to_const_hir(cx, e, lit),
+
+        hir::ExprUnary(op, ref inner) => {
+            let v = eval_hir_expr(cx, inner, visiting)?;
+            match (op, v) {
+                (hir::UnNeg, ConstValue::Int(i, ty)) => {
+                    let bits = int_bits(ty);
+                    i.checked_neg().filter(|i| int_fits(*i, bits)).map(|i| ConstValue::Int(i, ty))
+                }
+                (hir::UnNeg, ConstValue::F32(f)) => Some(ConstValue::F32(-f)),
+                (hir::UnNeg, ConstValue::F64(f)) => Some(ConstValue::F64(-f)),
+                (hir::UnNot, ConstValue::Bool(b)) => Some(ConstValue::Bool(!b)),
+                (hir::UnNot, ConstValue::Int(i, ty)) =>
+                    Some(ConstValue::Int(sign_extend(!i, int_bits(ty)), ty)),
+                (hir::UnNot, ConstValue::Uint(i, ty)) =>
+                    Some(ConstValue::Uint((!i) & uint_mask(uint_bits(ty)), ty)),
+                _ => None,
+            }
+        }
+
+        hir::ExprBinary(op, ref l, ref r) => {
+            let l = eval_hir_expr(cx, l, visiting)?;
+            let r = eval_hir_expr(cx, r, visiting)?;
+            eval_binop(hir_to_ast_binop(op.node), l, r)
+        }
+
+        hir::ExprPath(hir::QPath::Resolved(_, ref path)) => {
+            let def_id = path.def.opt_def_id()?;
+            if visiting.contains(&def_id) {
+                return None;
+            }
+            visiting.push(def_id);
+            let result = eval_const_item(cx, def_id, visiting);
+            visiting.pop();
+            result
+        }
+
+        _ => None,
+    }
+}
+
+fn lit_to_const_hir(cx: &driver::Ctxt, e: &hir::Expr, lit: &ast::Lit) -> Option<ConstValue> {
+    match lit.node {
+        LitKind::Bool(b) => Some(ConstValue::Bool(b)),
+        LitKind::Char(c) => Some(ConstValue::Char(c)),
+        LitKind::Int(v, _) => {
+            match cx.node_type(e.id).sty {
+                TypeVariants::TyInt(ty) => Some(ConstValue::Int(v as i128, ty)),
+                TypeVariants::TyUint(ty) => Some(ConstValue::Uint(v as u128, ty)),
+                _ => Some(ConstValue::Int(v as i128, IntTy::I32)),
+            }
+        }
+        _ => None,
+    }
+}
+
+fn hir_to_ast_binop(op: hir::BinOp_) -> BinOpKind {
+    use rustc::hir::BinOp_::*;
+    match op {
+        BiAdd => BinOpKind::Add,
+        BiSub => BinOpKind::Sub,
+        BiMul => BinOpKind::Mul,
+        BiDiv => BinOpKind::Div,
+        BiRem => BinOpKind::Rem,
+        BiAnd => BinOpKind::And,
+        BiOr => BinOpKind::Or,
+        BiBitXor => BinOpKind::BitXor,
+        BiBitAnd => BinOpKind::BitAnd,
+        BiBitOr => BinOpKind::BitOr,
+        BiShl => BinOpKind::Shl,
+        BiShr => BinOpKind::Shr,
+        BiEq => BinOpKind::Eq,
+        BiLt => BinOpKind::Lt,
+        BiLe => BinOpKind::Le,
+        BiNe => BinOpKind::Ne,
+        BiGe => BinOpKind::Ge,
+        BiGt => BinOpKind::Gt,
+    }
+}
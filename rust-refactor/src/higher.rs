@@ -0,0 +1,234 @@
+//! Recognizers for idiomatic high-level constructs that the parser has already desugared by the
+//! time a transform sees them.  `MatchCtxt`/`fold_match` only see the surface `ExprKind`/`StmtKind`
+//! that comes out of desugaring, so a transform that wants to act on "this is a `for` loop" has to
+//! write a brittle matcher against the exact desugared shape instead of asking for the loop
+//! directly.  This module does that recognition once, in one place, so `fold_nodes`/`visit_nodes`
+//! can be driven over the normalized construct.
+use syntax::ast::{Expr, ExprKind, StmtKind, Label, Pat, Block, RangeLimits};
+
+/// The parts of a `for pat in iterable { body }` loop, as produced by desugaring
+/// `match IntoIterator::into_iter(iterable) { mut iter => { loop { match Iterator::next(&mut iter)
+/// { Some(pat) => body, None => break } } } }`-ish shapes.
+pub struct ForLoop<'e> {
+    pub pat: &'e Pat,
+    pub iterable: &'e Expr,
+    pub body: &'e Block,
+    pub label: Option<Label>,
+}
+
+/// Recognize a desugared `for` loop.
+pub fn for_loop(e: &Expr) -> Option<ForLoop> {
+    // `match IntoIterator::into_iter(<iterable>) { mut iter => { loop { ... } } }`
+    let (scrut, arms) = match e.node {
+        ExprKind::Match(ref scrut, ref arms) if arms.len() == 1 => (scrut, arms),
+        _ => return None,
+    };
+    let iterable = match scrut.node {
+        ExprKind::Call(ref func, ref args) if args.len() == 1 => {
+            if !is_call_to(func, &["IntoIterator", "into_iter"]) {
+                return None;
+            }
+            &args[0]
+        }
+        _ => return None,
+    };
+
+    let arm_body = &arms[0].body;
+    let loop_block = match arm_body.node {
+        ExprKind::Block(ref b, _) => b,
+        _ => return None,
+    };
+    let (loop_label, loop_body) = match loop_block.stmts.last().map(|s| &s.node) {
+        Some(&StmtKind::Expr(ref e)) | Some(&StmtKind::Semi(ref e)) => {
+            match e.node {
+                ExprKind::Loop(ref body, label) => (label, body),
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+
+    // Inside the loop: `match Iterator::next(&mut iter) { Some(pat) => body, None => break }`
+    let next_match = match loop_body.stmts.first().map(|s| &s.node) {
+        Some(&StmtKind::Expr(ref e)) | Some(&StmtKind::Semi(ref e)) => e,
+        _ => return None,
+    };
+    let (next_scrut, next_arms) = match next_match.node {
+        ExprKind::Match(ref scrut, ref arms) => (scrut, arms),
+        _ => return None,
+    };
+    let next_is_iterator_next = match next_scrut.node {
+        ExprKind::Call(ref f, _) => is_call_to(f, &["Iterator", "next"]),
+        _ => false,
+    };
+    if !next_is_iterator_next {
+        return None;
+    }
+    let some_arm = next_arms.iter().find(|arm| arm_binds_some(arm))?;
+    let pat = some_arm_pat(some_arm)?;
+    let body = some_arm_block(some_arm)?;
+
+    Some(ForLoop { pat, iterable, body, label: loop_label })
+}
+
+/// Bounds of a `Range`/`RangeFrom`/`RangeTo`/`RangeInclusive` expr, however it was spelled:
+/// `a..b`, `a..=b`, `a..`, `..b`, or the desugared `std::ops::RangeInclusive::new(a, b)`.
+pub fn range(e: &Expr) -> Option<(Option<&Expr>, Option<&Expr>, RangeLimits)> {
+    match e.node {
+        ExprKind::Range(ref lo, ref hi, limits) =>
+            Some((lo.as_ref().map(|e| &**e), hi.as_ref().map(|e| &**e), limits)),
+        ExprKind::Call(ref func, ref args) if args.len() == 2 => {
+            if is_call_to(func, &["RangeInclusive", "new"]) {
+                Some((Some(&args[0]), Some(&args[1]), RangeLimits::Closed))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Recognize `if let pat = scrutinee { then } [else { els }]`, desugared to a one-or-two-arm
+/// `match`.
+pub fn if_let(e: &Expr) -> Option<(&Pat, &Expr, &Block, Option<&Expr>)> {
+    let (scrut, arms) = match e.node {
+        ExprKind::Match(ref scrut, ref arms) if arms.len() <= 2 => (scrut, arms),
+        _ => return None,
+    };
+    let then_arm = arms.get(0)?;
+    // A guard means this arm only fires conditionally, which `if let` (with no `if`-guard syntax
+    // of its own at this position) can never desugar to -- it's a genuine `match` whose guard
+    // would otherwise be silently dropped.
+    if then_arm.guard.is_some() {
+        return None;
+    }
+    let pat = then_arm.pats.get(0)?;
+    let then_block = match then_arm.body.node {
+        ExprKind::Block(ref b, _) => b,
+        _ => return None,
+    };
+    // The real `if let`/`while let` desugaring's fallback arm is always a bare wildcard -- any
+    // other second arm (even an exhaustive, refutable one) means this is a genuine `match` with
+    // its own bindings and behavior, not an `if let` with an implicit empty `else`.
+    let els = match arms.get(1) {
+        Some(arm) => {
+            if !is_wild_arm(arm) {
+                return None;
+            }
+            Some(&*arm.body)
+        }
+        None => None,
+    };
+    Some((pat, scrut, then_block, els))
+}
+
+/// Recognize `while let pat = scrutinee { body }`, desugared to `loop { match scrutinee { pat =>
+/// body, _ => break } }`.
+pub fn while_let<'e>(e: &'e Expr) -> Option<(&'e Pat, &'e Expr, &'e Block)> {
+    let (body, label) = match e.node {
+        ExprKind::Loop(ref body, label) => (body, label),
+        _ => return None,
+    };
+    let _ = label;
+    let inner = match body.stmts.first().map(|s| &s.node) {
+        Some(&StmtKind::Expr(ref e)) | Some(&StmtKind::Semi(ref e)) => e,
+        _ => return None,
+    };
+    let (scrut, arms) = match inner.node {
+        ExprKind::Match(ref scrut, ref arms) if arms.len() == 2 => (scrut, arms),
+        _ => return None,
+    };
+    // The fallback arm must be `_ => break`, not just any second arm -- otherwise this is a plain
+    // two-armed state-machine `loop { match s { A(x) => .., B(y) => .. } }` with no `break` at
+    // all, and treating its second arm as "the implicit while-let exit" would silently drop that
+    // arm's behavior and bindings.
+    if !is_wild_arm(&arms[1]) || !is_break_expr(&arms[1].body) {
+        return None;
+    }
+    // Same reasoning as `if_let`: a guard on the primary arm means this isn't really a `while
+    // let`, since there's no syntax for an extra guard condition there.
+    if arms[0].guard.is_some() {
+        return None;
+    }
+    let pat = arms[0].pats.get(0)?;
+    let body = match arms[0].body.node {
+        ExprKind::Block(ref b, _) => b,
+        _ => return None,
+    };
+    Some((pat, scrut, body))
+}
+
+/// Recognize `expr?`, desugared to a `match Try::into_result(expr) { Ok(v) => v, Err(e) => return
+/// Try::from_error(From::from(e)) }`.
+pub fn question_mark(e: &Expr) -> Option<&Expr> {
+    match e.node {
+        ExprKind::Try(ref inner) => Some(inner),
+        ExprKind::Match(ref scrut, ref arms) if arms.len() == 2 => {
+            match scrut.node {
+                ExprKind::Call(ref func, ref args) if args.len() == 1 &&
+                    is_call_to(func, &["Try", "into_result"]) => Some(&args[0]),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn is_call_to(func: &Expr, segs: &[&str]) -> bool {
+    let path = match func.node {
+        ExprKind::Path(_, ref path) => path,
+        _ => return false,
+    };
+    let n = path.segments.len();
+    n >= segs.len() &&
+        path.segments[n - segs.len()..].iter().zip(segs.iter())
+            .all(|(seg, want)| seg.identifier.name.as_str() == *want)
+}
+
+/// Is `arm`'s pattern a bare wildcard (`_`), with no guard?
+fn is_wild_arm(arm: &::syntax::ast::Arm) -> bool {
+    use syntax::ast::PatKind;
+    arm.guard.is_none() &&
+        arm.pats.len() == 1 &&
+        match arm.pats[0].node {
+            PatKind::Wild => true,
+            _ => false,
+        }
+}
+
+/// Is `e` a `break` with no label or value, as produced by the `while let` desugaring?
+fn is_break_expr(e: &Expr) -> bool {
+    match e.node {
+        ExprKind::Break(None, None) => true,
+        ExprKind::Block(ref b, _) => {
+            b.stmts.len() == 1 &&
+                match b.stmts[0].node {
+                    StmtKind::Expr(ref e) | StmtKind::Semi(ref e) => is_break_expr(e),
+                    _ => false,
+                }
+        }
+        _ => false,
+    }
+}
+
+fn arm_binds_some(arm: &::syntax::ast::Arm) -> bool {
+    some_arm_pat(arm).is_some()
+}
+
+fn some_arm_pat(arm: &::syntax::ast::Arm) -> Option<&Pat> {
+    use syntax::ast::PatKind;
+    let pat = arm.pats.get(0)?;
+    match pat.node {
+        PatKind::TupleStruct(ref path, ref pats, None) if pats.len() == 1 &&
+            path.segments.last().map(|s| s.identifier.name.as_str() == "Some").unwrap_or(false) =>
+            Some(&pats[0]),
+        _ => None,
+    }
+}
+
+fn some_arm_block(arm: &::syntax::ast::Arm) -> Option<&Block> {
+    match arm.body.node {
+        ExprKind::Block(ref b, _) => Some(b),
+        _ => None,
+    }
+}
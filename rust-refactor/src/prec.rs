@@ -0,0 +1,238 @@
+//! Precedence-aware parenthesization.  Used when splicing an already-parsed `Expr` into a new
+//! syntactic position (as `subst` does for bound metavariables) might otherwise change what the
+//! result parses back to -- e.g. matching `$e` against `a + b` and substituting into `$e * 2`
+//! would silently produce `a + b * 2` unless the `a + b` gets wrapped in parens first.
+use syntax::ast::{Arm, BinOpKind, Block, Expr, ExprKind, Stmt, StmtKind};
+use syntax::ptr::P;
+
+use make_ast::mk;
+
+/// Precedence level of an expr's top-level operator.  Ordered low-to-high, matching the table in
+/// the reference manual, restricted to the levels that matter once something is already an
+/// `ExprKind` (so no need to distinguish, say, `as` from unary minus beyond "both bind tighter
+/// than any binary op").
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Prec {
+    /// `return`, `break`, `yield`, closures, `=`/`+=`-style assignment.
+    Assign,
+    Range,
+    OrOr,
+    AndAnd,
+    Compare,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shift,
+    Additive,
+    Multiplicative,
+    Cast,
+    Unary,
+    /// Method/field/call/index/`?`.
+    Postfix,
+    /// Literals, paths, parenthesized/grouped exprs, blocks -- never need wrapping.
+    Atom,
+}
+
+fn bin_op_prec(op: BinOpKind) -> Prec {
+    use self::Prec::*;
+    match op {
+        BinOpKind::Or => OrOr,
+        BinOpKind::And => AndAnd,
+        BinOpKind::Eq | BinOpKind::Ne | BinOpKind::Lt | BinOpKind::Le |
+            BinOpKind::Gt | BinOpKind::Ge => Compare,
+        BinOpKind::BitOr => BitOr,
+        BinOpKind::BitXor => BitXor,
+        BinOpKind::BitAnd => BitAnd,
+        BinOpKind::Shl | BinOpKind::Shr => Shift,
+        BinOpKind::Add | BinOpKind::Sub => Additive,
+        BinOpKind::Mul | BinOpKind::Div | BinOpKind::Rem => Multiplicative,
+    }
+}
+
+/// Precedence of `e`'s top-level operator.
+pub fn prec(e: &Expr) -> Prec {
+    match e.node {
+        ExprKind::Closure(..) | ExprKind::Assign(..) | ExprKind::AssignOp(..) |
+            ExprKind::Ret(..) | ExprKind::Break(..) | ExprKind::Yield(..) => Prec::Assign,
+        ExprKind::Range(..) => Prec::Range,
+        ExprKind::Binary(op, ..) => bin_op_prec(op.node),
+        ExprKind::Cast(..) | ExprKind::Type(..) => Prec::Cast,
+        ExprKind::Unary(..) | ExprKind::Box(..) | ExprKind::AddrOf(..) => Prec::Unary,
+        ExprKind::Call(..) | ExprKind::MethodCall(..) | ExprKind::Field(..) |
+            ExprKind::TupField(..) | ExprKind::Index(..) | ExprKind::Try(..) => Prec::Postfix,
+        _ => Prec::Atom,
+    }
+}
+
+/// Syntactic position a child expr is being placed into.  Determines the precedence it must
+/// have, at minimum, to be emitted without parens.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HolePos {
+    BinLhs(BinOpKind),
+    BinRhs(BinOpKind),
+    UnaryOperand,
+    CastOperand,
+    /// Receiver of a method/field/index postfix expr.
+    Receiver,
+    /// Callee position of a `Call` (`f()`) -- needs at least postfix precedence, same as any
+    /// other postfix receiver, since e.g. a closure literal needs parens to be called directly.
+    Callee,
+}
+
+/// Minimum precedence required at `pos`, and whether a tie still needs parens.  True for the RHS
+/// of a left-associative operator (`a - (b - c)` and `a - b - c` aren't the same expr), and for
+/// *both* sides of a comparison operator: comparisons don't associate at all (`a < b > c` and
+/// `a == b == c` are parse errors, not just different exprs), so a comparison spliced into either
+/// side of another comparison always needs parens, never just when precedence ties.
+fn required(pos: HolePos) -> (Prec, bool) {
+    match pos {
+        HolePos::BinLhs(op) => (bin_op_prec(op), bin_op_prec(op) == Prec::Compare),
+        HolePos::BinRhs(op) => (bin_op_prec(op), true),
+        HolePos::UnaryOperand => (Prec::Unary, false),
+        HolePos::CastOperand => (Prec::Cast, false),
+        HolePos::Receiver | HolePos::Callee => (Prec::Postfix, false),
+    }
+}
+
+/// Whether `child`, spliced into `pos`, needs an `ExprKind::Paren` wrapper to preserve its
+/// meaning.
+pub fn needs_parens(child: &Expr, pos: HolePos) -> bool {
+    let child_prec = prec(child);
+    let (min, strict) = required(pos);
+    if strict { child_prec <= min } else { child_prec < min }
+}
+
+/// Wrap `e` in `ExprKind::Paren` if its precedence is too low for `pos`.
+pub fn rewrap(e: P<Expr>, pos: HolePos) -> P<Expr> {
+    if needs_parens(&e, pos) {
+        mk().paren_expr(e)
+    } else {
+        e
+    }
+}
+
+/// Recursively re-parenthesize every binary/unary/cast/postfix child of `e` whose precedence is
+/// too low for the position it sits in.  Applied to the result of a substitution, this makes the
+/// output semantically equivalent regardless of what shape the substituted subexpression had,
+/// without requiring `Subst` itself to track hole positions.
+pub fn rewrap_for_precedence(e: P<Expr>) -> P<Expr> {
+    e.map(|mut e| {
+        match e.node {
+            ExprKind::Binary(op, lhs, rhs) => {
+                let lhs = rewrap(rewrap_for_precedence(lhs), HolePos::BinLhs(op.node));
+                let rhs = rewrap(rewrap_for_precedence(rhs), HolePos::BinRhs(op.node));
+                e.node = ExprKind::Binary(op, lhs, rhs);
+            }
+            ExprKind::Unary(op, operand) => {
+                let operand = rewrap(rewrap_for_precedence(operand), HolePos::UnaryOperand);
+                e.node = ExprKind::Unary(op, operand);
+            }
+            ExprKind::Box(operand) => {
+                let operand = rewrap(rewrap_for_precedence(operand), HolePos::UnaryOperand);
+                e.node = ExprKind::Box(operand);
+            }
+            ExprKind::AddrOf(mutbl, operand) => {
+                let operand = rewrap(rewrap_for_precedence(operand), HolePos::UnaryOperand);
+                e.node = ExprKind::AddrOf(mutbl, operand);
+            }
+            ExprKind::Cast(operand, ty) => {
+                let operand = rewrap(rewrap_for_precedence(operand), HolePos::CastOperand);
+                e.node = ExprKind::Cast(operand, ty);
+            }
+            ExprKind::Call(func, args) => {
+                let func = rewrap(rewrap_for_precedence(func), HolePos::Callee);
+                let args = args.into_iter().map(rewrap_for_precedence).collect();
+                e.node = ExprKind::Call(func, args);
+            }
+            ExprKind::MethodCall(seg, mut args) => {
+                if !args.is_empty() {
+                    let recv = args.remove(0);
+                    let recv = rewrap(rewrap_for_precedence(recv), HolePos::Receiver);
+                    let rest = args.into_iter().map(rewrap_for_precedence);
+                    args = Some(recv).into_iter().chain(rest).collect();
+                }
+                e.node = ExprKind::MethodCall(seg, args);
+            }
+            ExprKind::Field(recv, ident) => {
+                let recv = rewrap(rewrap_for_precedence(recv), HolePos::Receiver);
+                e.node = ExprKind::Field(recv, ident);
+            }
+            ExprKind::TupField(recv, idx) => {
+                let recv = rewrap(rewrap_for_precedence(recv), HolePos::Receiver);
+                e.node = ExprKind::TupField(recv, idx);
+            }
+            ExprKind::Index(recv, index) => {
+                let recv = rewrap(rewrap_for_precedence(recv), HolePos::Receiver);
+                // The index itself sits inside `[...]`, so (like a `Call`'s args) it needs no
+                // wrapping of its own, just recursion into whatever it contains.
+                let index = rewrap_for_precedence(index);
+                e.node = ExprKind::Index(recv, index);
+            }
+            ExprKind::Try(operand) => {
+                let operand = rewrap(rewrap_for_precedence(operand), HolePos::Receiver);
+                e.node = ExprKind::Try(operand);
+            }
+            ExprKind::Paren(inner) => {
+                // Already explicitly parenthesized, so the inner expr needs no further wrapping
+                // at this level -- just recurse so anything nested inside it still gets fixed up.
+                let inner = rewrap_for_precedence(inner);
+                e.node = ExprKind::Paren(inner);
+            }
+            // None of these need wrapping at this level (a block's tail expr, a match arm's body,
+            // an `if`/`while` condition aren't binary/unary/cast/postfix operand positions), but a
+            // multi-statement `repl` template (`"{ let y = $e * 2; y }"`) splices its bound exprs
+            // somewhere inside one of these, so recursion has to keep going rather than stop here.
+            ExprKind::Block(block, label) => {
+                e.node = ExprKind::Block(rewrap_block(block), label);
+            }
+            ExprKind::If(cond, then_block, els) => {
+                let cond = rewrap_for_precedence(cond);
+                let then_block = rewrap_block(then_block);
+                let els = els.map(rewrap_for_precedence);
+                e.node = ExprKind::If(cond, then_block, els);
+            }
+            ExprKind::Match(scrut, arms) => {
+                let scrut = rewrap_for_precedence(scrut);
+                let arms = arms.into_iter().map(rewrap_arm).collect();
+                e.node = ExprKind::Match(scrut, arms);
+            }
+            ExprKind::Loop(block, label) => {
+                e.node = ExprKind::Loop(rewrap_block(block), label);
+            }
+            ExprKind::While(cond, block, label) => {
+                let cond = rewrap_for_precedence(cond);
+                e.node = ExprKind::While(cond, rewrap_block(block), label);
+            }
+            _ => {}
+        }
+        e
+    })
+}
+
+/// Recurse into every statement of a block (a tail expr is just its last stmt with no
+/// semicolon), leaving the block's own shape untouched.
+fn rewrap_block(block: P<Block>) -> P<Block> {
+    block.map(|mut b| {
+        b.stmts = b.stmts.into_iter().map(rewrap_stmt).collect();
+        b
+    })
+}
+
+fn rewrap_stmt(stmt: Stmt) -> Stmt {
+    let node = match stmt.node {
+        StmtKind::Expr(e) => StmtKind::Expr(rewrap_for_precedence(e)),
+        StmtKind::Semi(e) => StmtKind::Semi(rewrap_for_precedence(e)),
+        StmtKind::Local(local) => StmtKind::Local(local.map(|mut l| {
+            l.init = l.init.map(rewrap_for_precedence);
+            l
+        })),
+        other => other,
+    };
+    Stmt { node, ..stmt }
+}
+
+fn rewrap_arm(mut arm: Arm) -> Arm {
+    arm.guard = arm.guard.map(rewrap_for_precedence);
+    arm.body = rewrap_for_precedence(arm.body);
+    arm
+}
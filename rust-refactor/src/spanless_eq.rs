@@ -0,0 +1,314 @@
+//! Structural equality over `Expr`/`Stmt`/`Ty` that ignores `Span`s, `NodeId`s, and attributes
+//! (attributes are not compared at all).  Unlike the matcher (`MatchCtxt`/`fold_match`), which asks "does this tree match a
+//! pattern", this asks "are these two already-built trees the same tree" -- the operation needed
+//! by CSE-style transforms that want to bucket or compare subtrees they've already found, rather
+//! than match against a template.
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use syntax::ast::{Expr, ExprKind, Stmt, StmtKind, Ty, TyKind};
+
+use driver;
+use api::DriverCtxtExt;
+
+/// Context for spanless comparisons.  Carries the `driver::Ctxt` so that `Path` exprs/tys can be
+/// compared by resolved `DefId` rather than by spelling, when `resolve` is set.
+pub struct SpanlessEqCtxt<'a, 'hir: 'a, 'gcx: 'a + 'hir, 'tcx: 'a> {
+    cx: &'a driver::Ctxt<'a, 'hir, 'gcx, 'tcx>,
+    /// When true, two `Path` exprs/tys that both resolve are compared by `DefId`.  When either
+    /// side doesn't resolve, falls back to segment-by-segment identifier comparison either way.
+    resolve: bool,
+}
+
+impl<'a, 'hir, 'gcx, 'tcx> SpanlessEqCtxt<'a, 'hir, 'gcx, 'tcx> {
+    pub fn new(cx: &'a driver::Ctxt<'a, 'hir, 'gcx, 'tcx>) -> Self {
+        SpanlessEqCtxt { cx, resolve: true }
+    }
+
+    /// Compare without attempting path resolution, for use on exprs/tys that haven't been
+    /// typechecked (e.g. freshly-parsed `repl` patterns).
+    pub fn without_resolve(mut self) -> Self {
+        self.resolve = false;
+        self
+    }
+
+    fn paths_equal_by_idents(a: &syntax::ast::Path, b: &syntax::ast::Path) -> bool {
+        a.segments.len() == b.segments.len() &&
+            a.segments.iter().zip(b.segments.iter())
+                .all(|(sa, sb)| sa.identifier.name == sb.identifier.name)
+    }
+
+    fn expr_paths_equal(&self, a: &Expr, b: &Expr) -> bool {
+        if self.resolve {
+            if let (Some(da), Some(db)) = (self.cx.try_resolve_expr(a), self.cx.try_resolve_expr(b)) {
+                return da == db;
+            }
+        }
+        let (pa, pb) = match (&a.node, &b.node) {
+            (&ExprKind::Path(_, ref pa), &ExprKind::Path(_, ref pb)) => (pa, pb),
+            _ => return false,
+        };
+        Self::paths_equal_by_idents(pa, pb)
+    }
+
+    fn ty_paths_equal(&self, a: &Ty, b: &Ty) -> bool {
+        if self.resolve {
+            if let (Some(da), Some(db)) = (self.cx.try_resolve_ty(a), self.cx.try_resolve_ty(b)) {
+                return da == db;
+            }
+        }
+        let (pa, pb) = match (&a.node, &b.node) {
+            (&TyKind::Path(_, ref pa), &TyKind::Path(_, ref pb)) => (pa, pb),
+            _ => return false,
+        };
+        Self::paths_equal_by_idents(pa, pb)
+    }
+
+    /// Are `a` and `b` the same expr, ignoring spans/`NodeId`s/attributes?  Only the kinds
+    /// matched below are compared structurally; any other kind (blocks, `if`, `match`, closures,
+    /// ...) compares unequal, even to an identical copy of itself.
+    pub fn exprs_equal(&self, a: &Expr, b: &Expr) -> bool {
+        match (&a.node, &b.node) {
+            (&ExprKind::Path(..), &ExprKind::Path(..)) => self.expr_paths_equal(a, b),
+
+            (&ExprKind::Box(ref ea), &ExprKind::Box(ref eb)) => self.exprs_equal(ea, eb),
+
+            (&ExprKind::Array(ref esa), &ExprKind::Array(ref esb)) |
+            (&ExprKind::Tup(ref esa), &ExprKind::Tup(ref esb)) =>
+                self.expr_lists_equal(esa, esb),
+
+            (&ExprKind::Call(ref fa, ref argsa), &ExprKind::Call(ref fb, ref argsb)) =>
+                self.exprs_equal(fa, fb) && self.expr_lists_equal(argsa, argsb),
+
+            (&ExprKind::MethodCall(ref sega, ref argsa),
+             &ExprKind::MethodCall(ref segb, ref argsb)) =>
+                sega.identifier.name == segb.identifier.name &&
+                    self.expr_lists_equal(argsa, argsb),
+
+            (&ExprKind::Binary(opa, ref la, ref ra), &ExprKind::Binary(opb, ref lb, ref rb)) =>
+                opa.node == opb.node && self.exprs_equal(la, lb) && self.exprs_equal(ra, rb),
+
+            (&ExprKind::Unary(opa, ref ea), &ExprKind::Unary(opb, ref eb)) =>
+                opa == opb && self.exprs_equal(ea, eb),
+
+            (&ExprKind::Lit(ref la), &ExprKind::Lit(ref lb)) => la.node == lb.node,
+
+            (&ExprKind::Cast(ref ea, ref tya), &ExprKind::Cast(ref eb, ref tyb)) |
+            (&ExprKind::Type(ref ea, ref tya), &ExprKind::Type(ref eb, ref tyb)) =>
+                self.exprs_equal(ea, eb) && self.tys_equal(tya, tyb),
+
+            (&ExprKind::Field(ref ea, ida), &ExprKind::Field(ref eb, idb)) =>
+                ida.node.name == idb.node.name && self.exprs_equal(ea, eb),
+
+            (&ExprKind::Index(ref ea, ref ia), &ExprKind::Index(ref eb, ref ib)) =>
+                self.exprs_equal(ea, eb) && self.exprs_equal(ia, ib),
+
+            (&ExprKind::Paren(ref ea), _) => self.exprs_equal(ea, b),
+            (_, &ExprKind::Paren(ref eb)) => self.exprs_equal(a, eb),
+
+            (&ExprKind::AddrOf(mta, ref ea), &ExprKind::AddrOf(mtb, ref eb)) =>
+                mta == mtb && self.exprs_equal(ea, eb),
+
+            (&ExprKind::Repeat(ref ea, ref na), &ExprKind::Repeat(ref eb, ref nb)) =>
+                self.exprs_equal(ea, eb) && self.exprs_equal(na, nb),
+
+            _ => false,
+        }
+    }
+
+    fn expr_lists_equal(&self, a: &[syntax::ptr::P<Expr>], b: &[syntax::ptr::P<Expr>]) -> bool {
+        a.len() == b.len() && a.iter().zip(b.iter()).all(|(ea, eb)| self.exprs_equal(ea, eb))
+    }
+
+    /// Are `a` and `b` the same stmt, ignoring spans/`NodeId`s.  Only expression stmts (with or
+    /// without a trailing semicolon) are compared; `let` stmts, items and macro stmts compare
+    /// unequal.
+    pub fn stmts_equal(&self, a: &Stmt, b: &Stmt) -> bool {
+        match (&a.node, &b.node) {
+            (&StmtKind::Expr(ref ea), &StmtKind::Expr(ref eb)) |
+            (&StmtKind::Semi(ref ea), &StmtKind::Semi(ref eb)) => self.exprs_equal(ea, eb),
+            _ => false,
+        }
+    }
+
+    /// Are `a` and `b` the same ty, ignoring spans/`NodeId`s.  Only path, reference, slice and
+    /// tuple types are compared; any other type compares unequal.
+    pub fn tys_equal(&self, a: &Ty, b: &Ty) -> bool {
+        match (&a.node, &b.node) {
+            (&TyKind::Path(..), &TyKind::Path(..)) => self.ty_paths_equal(a, b),
+            (&TyKind::Rptr(_, ref mta), &TyKind::Rptr(_, ref mtb)) =>
+                mta.mutbl == mtb.mutbl && self.tys_equal(&mta.ty, &mtb.ty),
+            (&TyKind::Slice(ref ta), &TyKind::Slice(ref tb)) => self.tys_equal(ta, tb),
+            (&TyKind::Tup(ref tsa), &TyKind::Tup(ref tsb)) =>
+                tsa.len() == tsb.len() &&
+                    tsa.iter().zip(tsb.iter()).all(|(ta, tb)| self.tys_equal(ta, tb)),
+            _ => false,
+        }
+    }
+}
+
+/// Convenience wrapper around `SpanlessEqCtxt::exprs_equal` for one-off comparisons.
+pub fn exprs_equal(cx: &driver::Ctxt, a: &Expr, b: &Expr) -> bool {
+    SpanlessEqCtxt::new(cx).exprs_equal(a, b)
+}
+
+/// A spanless structural hash of `e`, resolving `Path` exprs through `cx` the same way
+/// `exprs_equal`'s default (`resolve: true`) does: `exprs_equal(cx, a, b)` implies
+/// `hash_expr(cx, a) == hash_expr(cx, b)`, including when `a`/`b` are differently-spelled paths
+/// that resolve to the same `DefId` (e.g. `std::mem::size_of` vs. an imported `size_of`).  The
+/// implication holds when the compared paths resolve alike: a path that resolves and one that
+/// doesn't can compare equal by spelling under `exprs_equal` yet hash differently here.
+/// Intended for bucketing candidate subtrees into a `HashMap` before confirming equality with
+/// `exprs_equal`, not as a replacement for it -- two *different* `DefId`s can still hash-collide,
+/// so `exprs_equal` remains the source of truth.
+pub fn hash_expr(cx: &driver::Ctxt, e: &Expr) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hash_expr_into(Some(cx), e, &mut hasher);
+    hasher.finish()
+}
+
+/// Like `hash_expr`, but for exprs that haven't been typechecked (e.g. freshly-parsed `repl`
+/// patterns) and so can't be resolved -- paths always hash by spelling.  Consistent only with
+/// `SpanlessEqCtxt::without_resolve().exprs_equal(a, b)`, not with the resolve-aware default.
+pub fn hash_expr_unresolved(e: &Expr) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hash_expr_into(None, e, &mut hasher);
+    hasher.finish()
+}
+
+fn hash_expr_into<H: Hasher>(cx: Option<&driver::Ctxt>, e: &Expr, h: &mut H) {
+    match e.node {
+        ExprKind::Paren(ref inner) => hash_expr_into(cx, inner, h),
+
+        ExprKind::Path(_, ref p) => {
+            0u8.hash(h);
+            match cx.and_then(|cx| cx.try_resolve_expr(e)) {
+                Some(def_id) => {
+                    1u8.hash(h);
+                    def_id.hash(h);
+                }
+                None => {
+                    0u8.hash(h);
+                    for seg in &p.segments {
+                        seg.identifier.name.hash(h);
+                    }
+                }
+            }
+        }
+        ExprKind::Box(ref inner) => {
+            1u8.hash(h);
+            hash_expr_into(cx, inner, h);
+        }
+        ExprKind::Array(ref es) | ExprKind::Tup(ref es) => {
+            2u8.hash(h);
+            for e in es {
+                hash_expr_into(cx, e, h);
+            }
+        }
+        ExprKind::Call(ref f, ref args) => {
+            3u8.hash(h);
+            hash_expr_into(cx, f, h);
+            for a in args {
+                hash_expr_into(cx, a, h);
+            }
+        }
+        ExprKind::MethodCall(ref seg, ref args) => {
+            4u8.hash(h);
+            seg.identifier.name.hash(h);
+            for a in args {
+                hash_expr_into(cx, a, h);
+            }
+        }
+        ExprKind::Binary(op, ref l, ref r) => {
+            5u8.hash(h);
+            op.node.hash(h);
+            hash_expr_into(cx, l, h);
+            hash_expr_into(cx, r, h);
+        }
+        ExprKind::Unary(op, ref inner) => {
+            6u8.hash(h);
+            op.hash(h);
+            hash_expr_into(cx, inner, h);
+        }
+        ExprKind::Lit(ref lit) => {
+            7u8.hash(h);
+            format!("{:?}", lit.node).hash(h);
+        }
+        ExprKind::Field(ref inner, ident) => {
+            8u8.hash(h);
+            ident.node.name.hash(h);
+            hash_expr_into(cx, inner, h);
+        }
+        ExprKind::Index(ref e, ref i) => {
+            9u8.hash(h);
+            hash_expr_into(cx, e, h);
+            hash_expr_into(cx, i, h);
+        }
+        ExprKind::Cast(ref inner, ref ty) => {
+            10u8.hash(h);
+            hash_expr_into(cx, inner, h);
+            hash_ty_into(cx, ty, h);
+        }
+        ExprKind::Type(ref inner, ref ty) => {
+            11u8.hash(h);
+            hash_expr_into(cx, inner, h);
+            hash_ty_into(cx, ty, h);
+        }
+        ExprKind::AddrOf(mutbl, ref inner) => {
+            12u8.hash(h);
+            mutbl.hash(h);
+            hash_expr_into(cx, inner, h);
+        }
+        ExprKind::Repeat(ref inner, ref n) => {
+            13u8.hash(h);
+            hash_expr_into(cx, inner, h);
+            hash_expr_into(cx, n, h);
+        }
+        _ => {
+            255u8.hash(h);
+        }
+    }
+}
+
+/// Spanless structural hash of a `Ty`, consistent with `SpanlessEqCtxt::tys_equal`'s recursion
+/// (resolved `DefId` or segment identifiers for `Path`, recursion for `Rptr`/`Slice`/`Tup`); other
+/// shapes fall back to a single tag rather than a full `Debug` dump, since `ast::Ty`'s derived
+/// `Debug` includes spans and would otherwise break the "equal trees hash equal" property this
+/// exists for.
+fn hash_ty_into<H: Hasher>(cx: Option<&driver::Ctxt>, ty: &Ty, h: &mut H) {
+    match ty.node {
+        TyKind::Path(_, ref p) => {
+            0u8.hash(h);
+            match cx.and_then(|cx| cx.try_resolve_ty(ty)) {
+                Some(def_id) => {
+                    1u8.hash(h);
+                    def_id.hash(h);
+                }
+                None => {
+                    0u8.hash(h);
+                    for seg in &p.segments {
+                        seg.identifier.name.hash(h);
+                    }
+                }
+            }
+        }
+        TyKind::Rptr(_, ref mt) => {
+            1u8.hash(h);
+            mt.mutbl.hash(h);
+            hash_ty_into(cx, &mt.ty, h);
+        }
+        TyKind::Slice(ref inner) => {
+            2u8.hash(h);
+            hash_ty_into(cx, inner, h);
+        }
+        TyKind::Tup(ref tys) => {
+            3u8.hash(h);
+            for t in tys {
+                hash_ty_into(cx, t, h);
+            }
+        }
+        _ => {
+            255u8.hash(h);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! Usage and mutation analysis for local bindings.  Transforms like let-to-const promotion,
+//! variable inlining, and `mut` removal all need to know, for a single local, whether it's ever
+//! written after its initializer and whether its address ever escapes -- information the
+//! expr/pattern matchers can't give, since it's a property of the binding across its whole scope
+//! rather than of any one matched subtree.
+use std::collections::HashSet;
+
+use rustc::hir::def_id::DefId;
+use rustc::ty::TypeVariants;
+use syntax::ast::{Expr, ExprKind, Mutability, NodeId};
+
+use api::DriverCtxtExt;
+use driver;
+use fold::Fold;
+use visit_node::visit_nodes;
+
+/// Every read, write, and mutable borrow of a local binding, collected over the scope it's valid
+/// in.
+#[derive(Clone, Debug, Default)]
+pub struct Uses {
+    /// Places the binding is read: used as an rvalue, or as the receiver of a non-`&mut self`
+    /// method/field access.
+    pub reads: Vec<NodeId>,
+    /// Places the binding is written: the lvalue of `=`/`+=`-style assignment.
+    pub writes: Vec<NodeId>,
+    /// Places a `&mut` reference to the binding is taken, whether explicitly (`&mut x`) or via
+    /// autoref on a `&mut self` method receiver.
+    pub borrows_mut: Vec<NodeId>,
+    /// Places an immutable `&` reference to the binding is taken explicitly (`&x`).  Distinct
+    /// from `reads`: `let p = &x;` lets `p` outlive this use and alias `x`'s address, which a
+    /// plain by-value read (`let y = x;`) never does -- promoting `x` to a `const` is only safe
+    /// when this (and `borrows_mut`) are both empty.
+    pub addr_taken: Vec<NodeId>,
+}
+
+impl Uses {
+    /// Does anything besides the initializer ever write to, or take a `&mut` of, this binding?
+    pub fn is_mutated(&self) -> bool {
+        !self.writes.is_empty() || !self.borrows_mut.is_empty()
+    }
+
+    /// Does the binding's address ever escape (get taken by reference, mutable or not)?  A local
+    /// promotable to a `const` must answer `false` here.
+    pub fn escapes(&self) -> bool {
+        !self.borrows_mut.is_empty() || !self.addr_taken.is_empty()
+    }
+}
+
+/// Collect every use, within `scope`, of the local binding whose `Pat` has id `binding_id`.
+pub fn local_uses<T: Fold>(cx: &driver::Ctxt, binding_id: NodeId, scope: &T) -> Uses {
+    let mut uses = Uses::default();
+    let mut handled = HashSet::new();
+
+    // First pass: positions with a distinguished lvalue/receiver -- these determine whether the
+    // binding's *own* `Path` expr at that position is a write or a mutable borrow rather than a
+    // plain read, so they have to be checked before the path itself is classified.
+    visit_nodes(scope, |e: &Expr| {
+        match e.node {
+            ExprKind::Assign(ref lhs, _) | ExprKind::AssignOp(_, ref lhs, _) => {
+                if let Some(id) = resolves_to(cx, lhs, binding_id) {
+                    uses.writes.push(id);
+                    handled.insert(id);
+                }
+            }
+            ExprKind::AddrOf(Mutability::Mutable, ref inner) => {
+                if let Some(id) = resolves_to(cx, inner, binding_id) {
+                    uses.borrows_mut.push(id);
+                    handled.insert(id);
+                }
+            }
+            ExprKind::AddrOf(Mutability::Immutable, ref inner) => {
+                if let Some(id) = resolves_to(cx, inner, binding_id) {
+                    uses.addr_taken.push(id);
+                    handled.insert(id);
+                }
+            }
+            ExprKind::MethodCall(_, ref args) => {
+                if let (Some(recv), true) = (args.first(), is_mut_self_call(cx, e)) {
+                    if let Some(id) = resolves_to(cx, recv, binding_id) {
+                        uses.borrows_mut.push(id);
+                        handled.insert(id);
+                    }
+                }
+            }
+            _ => {}
+        }
+    });
+
+    // Second pass: every remaining `Path` expr resolving to the binding is a plain read, unless
+    // the compiler inserted a `&mut` autoref there (e.g. the base `v` of `v[i] = x` with
+    // `v: Vec<T>`, borrowed for `IndexMut` -- the adjustment only shows up in the typeck tables,
+    // not the ast).  `&mut self` method receivers were already classified by the first pass.
+    visit_nodes(scope, |e: &Expr| {
+        if handled.contains(&e.id) {
+            return;
+        }
+        if resolves_to(cx, e, binding_id).is_none() {
+            return;
+        }
+        if is_mut_autoref(cx, e) {
+            uses.borrows_mut.push(e.id);
+        } else {
+            uses.reads.push(e.id);
+        }
+    });
+
+    uses
+}
+
+/// Is `e` itself (not some subexpression of it) a `Path` expr resolving to `binding_id`?
+fn resolves_to(cx: &driver::Ctxt, e: &Expr, binding_id: NodeId) -> Option<NodeId> {
+    if let ExprKind::Path(..) = e.node {
+        let def_id = cx.try_resolve_expr(e)?;
+        let local_id = cx.hir_map().as_local_node_id(def_id)?;
+        if local_id == binding_id {
+            return Some(e.id);
+        }
+    }
+    None
+}
+
+/// Does the method called at `e` take `&mut self`?
+fn is_mut_self_call(cx: &driver::Ctxt, e: &Expr) -> bool {
+    match cx.opt_callee(e) {
+        Some(def_id) => self_is_mut_ref(cx, def_id),
+        None => false,
+    }
+}
+
+fn self_is_mut_ref(cx: &driver::Ctxt, def_id: DefId) -> bool {
+    let sig = cx.def_type(def_id).fn_sig(cx.ty_ctxt());
+    match sig.skip_binder().inputs().get(0).map(|t| &t.sty) {
+        Some(&TypeVariants::TyRef(_, mt)) => mt.mutbl == Mutability::Mutable,
+        _ => false,
+    }
+}
+
+/// Whether the compiler inserted a `&mut` autoref adjustment at `e`.
+fn is_mut_autoref(cx: &driver::Ctxt, e: &Expr) -> bool {
+    let adjusted = cx.adjusted_node_type(e.id);
+    let plain = cx.node_type(e.id);
+    if adjusted == plain {
+        return false;
+    }
+    match adjusted.sty {
+        TypeVariants::TyRef(_, mt) => mt.mutbl == Mutability::Mutable,
+        _ => false,
+    }
+}